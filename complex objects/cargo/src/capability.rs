@@ -0,0 +1,574 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Capability tokens that authorize sync operations against a `Store`.
+//!
+//! A token grants a set of `Capability`s to an `audience` DID/key, signed by
+//! an `issuer`. Tokens may be delegated: a delegated token's `proof` points at
+//! the parent token it was carved out of, and its own capabilities must be a
+//! non-expanding subset (attenuation) of the parent's. Verifying a token walks
+//! the whole `proof` chain back to a root token minted by the store owner.
+//!
+//! Revocation: every hop carries a `jti` and an optional `expires_at`.
+//! `expires_at` bounds how long a leaked token stays usable without needing
+//! any server-side state, and is itself attenuating - `delegate` rejects a
+//! child whose expiry would outlive its parent's. `jti` is there so a caller
+//! can reject one specific hop mid-lifetime, but this crate doesn't keep a
+//! revocation list itself; `verify`/`authorize` take a `RevocationList` the
+//! caller plugs in (`()` if they don't have one wired up yet).
+
+extern crate base64;
+extern crate ed25519_dalek;
+// Only pulled in for `Ed25519Signer::generate_signing_key`'s `OsRng`, which
+// is gated off wasm32 below - see the comment on that method.
+#[cfg(not(target_arch = "wasm32"))]
+extern crate rand;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use self::ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature};
+#[cfg(not(target_arch = "wasm32"))]
+use self::rand::rngs::OsRng;
+
+/// A single permitted action on a resource, e.g. `list:<uuid>` + `todo/write`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capability {
+    pub resource: String,
+    pub ability: String,
+}
+
+/// A signed, delegatable authorization token.
+///
+/// `proof` is `None` for a root token minted directly by the store owner, and
+/// `Some` for a token delegated from a parent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapabilityToken {
+    pub issuer: String,
+    pub audience: String,
+    pub capabilities: Vec<Capability>,
+    pub proof: Option<Box<CapabilityToken>>,
+    /// Identifies this hop independent of its parent's, so a caller's
+    /// revocation list can name and reject one specific delegated token
+    /// without rotating every key in the chain.
+    pub jti: String,
+    /// Unix timestamp (seconds) after which this hop is no longer valid, or
+    /// `None` if it never expires.
+    pub expires_at: Option<i64>,
+    pub signature: String,
+}
+
+#[derive(Debug)]
+pub enum CapabilityError {
+    /// A signature in the proof chain didn't validate.
+    InvalidSignature,
+    /// A delegated token claimed a capability its parent didn't hold.
+    CapabilitiesExpanded,
+    /// A delegated token's `expires_at` outlives its parent's.
+    LifetimeExpanded,
+    /// The root of the proof chain wasn't issued by the expected store owner.
+    RootIssuerMismatch,
+    /// A hop in the proof chain is past its `expires_at`.
+    Expired,
+    /// A hop in the proof chain's `jti` is on the caller's revocation list.
+    Revoked,
+    /// A token was verified and well-formed, but doesn't grant the requested
+    /// resource/ability pair.
+    NotAuthorized,
+    /// This signer holds no signing key for the requested issuer DID.
+    SigningKeyUnavailable,
+    /// Key material (signing or public) wasn't a valid ed25519 key.
+    MalformedKey,
+}
+
+impl fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CapabilityError::InvalidSignature => write!(f, "invalid signature in capability chain"),
+            CapabilityError::CapabilitiesExpanded => write!(f, "delegated token expands its parent's capabilities"),
+            CapabilityError::LifetimeExpanded => write!(f, "delegated token outlives its parent's expiry"),
+            CapabilityError::RootIssuerMismatch => write!(f, "root token issuer does not match store owner"),
+            CapabilityError::Expired => write!(f, "token has expired"),
+            CapabilityError::Revoked => write!(f, "token has been revoked"),
+            CapabilityError::NotAuthorized => write!(f, "token does not grant the requested capability"),
+            CapabilityError::SigningKeyUnavailable => write!(f, "no signing key held for this issuer"),
+            CapabilityError::MalformedKey => write!(f, "malformed ed25519 key material"),
+        }
+    }
+}
+
+/// Consulted during `verify`/`authorize` to reject one specific hop in the
+/// proof chain by its `jti`, without needing to rotate every signing key in
+/// the chain. This crate doesn't keep such a list itself - callers plug in
+/// whatever backs their own store. `()` is provided below for callers that
+/// don't have one wired up yet and only want `expires_at` enforcement.
+pub trait RevocationList {
+    fn is_revoked(&self, jti: &str) -> bool;
+}
+
+impl RevocationList for () {
+    fn is_revoked(&self, _jti: &str) -> bool {
+        false
+    }
+}
+
+/// Signs and verifies the bytes of a `CapabilityToken`, independent of the
+/// wire format above. `issuer` identifies the DID whose key should be used -
+/// implementations must key signing/verification per DID rather than sharing
+/// one secret across every issuer, or a single leaked token lets the holder
+/// forge tokens for any other identity. See `Ed25519Signer` below for the
+/// implementation backing the FFI surface.
+pub trait TokenSigner {
+    fn sign(&self, issuer: &str, payload: &str) -> Result<String, CapabilityError>;
+    fn verify(&self, issuer: &str, payload: &str, signature: &str) -> bool;
+}
+
+impl CapabilityToken {
+    /// Mint a root token signed by `issuer`, with no parent proof.
+    /// `jti` identifies this hop for a caller's revocation list; `expires_at`
+    /// is a Unix timestamp (seconds) after which the token stops validating,
+    /// or `None` for a token that never expires.
+    pub fn root<S: TokenSigner>(
+        signer: &S,
+        issuer: String,
+        audience: String,
+        capabilities: Vec<Capability>,
+        jti: String,
+        expires_at: Option<i64>,
+    ) -> Result<CapabilityToken, CapabilityError> {
+        let payload = signing_payload(&issuer, &audience, &capabilities, &jti, expires_at);
+        let signature = signer.sign(&issuer, &payload)?;
+        Ok(CapabilityToken {
+            issuer,
+            audience,
+            capabilities,
+            proof: None,
+            jti,
+            expires_at,
+            signature,
+        })
+    }
+
+    /// Delegate a subset of this token's capabilities to a new audience.
+    ///
+    /// Returns `CapabilitiesExpanded` if `capabilities` isn't a subset of the
+    /// ones already held by this token, `LifetimeExpanded` if `expires_at`
+    /// would outlive this token's own expiry, or `SigningKeyUnavailable` if
+    /// `signer` can't sign on behalf of this token's `audience` (the
+    /// delegator, who becomes the new token's issuer).
+    pub fn delegate<S: TokenSigner>(
+        &self,
+        signer: &S,
+        audience: String,
+        capabilities: Vec<Capability>,
+        jti: String,
+        expires_at: Option<i64>,
+    ) -> Result<CapabilityToken, CapabilityError> {
+        if !capabilities.iter().all(|wanted| self.capabilities.contains(wanted)) {
+            return Err(CapabilityError::CapabilitiesExpanded);
+        }
+        if let Some(parent_expiry) = self.expires_at {
+            if expires_at.map_or(true, |expiry| expiry > parent_expiry) {
+                return Err(CapabilityError::LifetimeExpanded);
+            }
+        }
+        let issuer = self.audience.clone();
+        let payload = signing_payload(&issuer, &audience, &capabilities, &jti, expires_at);
+        let signature = signer.sign(&issuer, &payload)?;
+        Ok(CapabilityToken {
+            issuer,
+            audience,
+            capabilities,
+            proof: Some(Box::new(self.clone())),
+            jti,
+            expires_at,
+            signature,
+        })
+    }
+
+    /// Walk the proof chain, verifying that every hop's signature validates,
+    /// that each delegated token's capabilities only attenuate its parent's,
+    /// that no hop is expired or revoked (per `revoked`, as of `now`), and
+    /// that the chain bottoms out at a root token issued by `store_owner`.
+    pub fn verify<S: TokenSigner, R: RevocationList>(
+        &self,
+        signer: &S,
+        store_owner: &str,
+        revoked: &R,
+        now: i64,
+    ) -> Result<(), CapabilityError> {
+        let mut current = self;
+        loop {
+            if revoked.is_revoked(&current.jti) {
+                return Err(CapabilityError::Revoked);
+            }
+            if let Some(expires_at) = current.expires_at {
+                if now >= expires_at {
+                    return Err(CapabilityError::Expired);
+                }
+            }
+            let payload = signing_payload(&current.issuer, &current.audience, &current.capabilities, &current.jti, current.expires_at);
+            if !signer.verify(&current.issuer, &payload, &current.signature) {
+                return Err(CapabilityError::InvalidSignature);
+            }
+            match current.proof {
+                Some(ref parent) => {
+                    if !current.capabilities.iter().all(|c| parent.capabilities.contains(c)) {
+                        return Err(CapabilityError::CapabilitiesExpanded);
+                    }
+                    if current.issuer != parent.audience {
+                        return Err(CapabilityError::InvalidSignature);
+                    }
+                    current = parent;
+                }
+                None => {
+                    if current.issuer != store_owner {
+                        return Err(CapabilityError::RootIssuerMismatch);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Verify this token against `signer`/`store_owner`/`revoked` as of
+    /// `now`, then check that it grants `ability` on `resource`. This is the
+    /// check every `Store` operation gated by a capability token should call.
+    pub fn authorize<S: TokenSigner, R: RevocationList>(
+        &self,
+        signer: &S,
+        store_owner: &str,
+        revoked: &R,
+        now: i64,
+        resource: &str,
+        ability: &str,
+    ) -> Result<(), CapabilityError> {
+        self.verify(signer, store_owner, revoked, now)?;
+        if self.allows(resource, ability) {
+            Ok(())
+        } else {
+            Err(CapabilityError::NotAuthorized)
+        }
+    }
+
+    /// True if this (already-verified) token grants `ability` on `resource`.
+    pub fn allows(&self, resource: &str, ability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|c| c.resource == resource && c.ability == ability)
+    }
+}
+
+/// Length-prefix `field` onto `payload` as `"<byte-len>:<field>"`, so the
+/// boundary between it and whatever comes next can never be confused with
+/// bytes that happen to appear inside `field` itself.
+fn push_field(payload: &mut String, field: &str) {
+    payload.push_str(&field.len().to_string());
+    payload.push(':');
+    payload.push_str(field);
+}
+
+/// Serialize `(issuer, audience, capabilities, jti, expires_at)` into an
+/// unambiguous byte string to sign/verify. Every field is length-prefixed
+/// rather than joined with a bare delimiter - plain `"{}|{}"`-style joins let
+/// distinct tuples (e.g. an `audience` containing `|` plus no capabilities,
+/// versus a shorter `audience` plus a capability that supplies the rest of
+/// those bytes) collide onto the same signed payload, so a signature meant
+/// for one token would also validate for the other. `jti`/`expires_at` are
+/// signed too, so a holder can't strip or extend a token's expiry, or swap
+/// its `jti` to dodge a revocation list entry, without invalidating the
+/// signature.
+fn signing_payload(issuer: &str, audience: &str, capabilities: &[Capability], jti: &str, expires_at: Option<i64>) -> String {
+    let mut payload = String::new();
+    push_field(&mut payload, issuer);
+    push_field(&mut payload, audience);
+    payload.push_str(&capabilities.len().to_string());
+    payload.push(':');
+    for capability in capabilities {
+        push_field(&mut payload, &capability.resource);
+        push_field(&mut payload, &capability.ability);
+    }
+    push_field(&mut payload, jti);
+    push_field(&mut payload, &expires_at.map(|expiry| expiry.to_string()).unwrap_or_default());
+    payload
+}
+
+/// A `TokenSigner` backed by real per-DID ed25519 keys rather than a shared
+/// secret: each DID's signature can only be produced by that DID's own key,
+/// and verification only ever needs that DID's *public* key, so handing a
+/// token (or even this signer's full verify-key set) to another party never
+/// leaks the ability to forge a different issuer's signature.
+pub struct Ed25519Signer {
+    signing_keys: HashMap<String, Keypair>,
+    verify_keys: HashMap<String, PublicKey>,
+}
+
+impl Ed25519Signer {
+    pub fn new() -> Ed25519Signer {
+        Ed25519Signer {
+            signing_keys: HashMap::new(),
+            verify_keys: HashMap::new(),
+        }
+    }
+
+    /// Generate a fresh keypair for `did` and hold onto the private half, so
+    /// this signer can sign tokens issued by `did`.
+    ///
+    /// Not available on wasm32: `OsRng` needs a real OS entropy source that
+    /// `wasm32-unknown-unknown` doesn't provide without an explicit
+    /// `getrandom` backend wired in by the host (there's no `Cargo.toml` in
+    /// this tree to pin that feature). wasm callers only ever verify or
+    /// delegate with keys loaded via `load_signing_key`/`trust_public_key`,
+    /// never mint fresh ones, so they don't need this.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn generate_signing_key(&mut self, did: String) {
+        let mut csprng: OsRng = OsRng::new().expect("a secure OS RNG must be available");
+        let keypair = Keypair::generate(&mut csprng);
+        self.verify_keys.insert(did.clone(), keypair.public);
+        self.signing_keys.insert(did, keypair);
+    }
+
+    /// Load a known ed25519 secret key for `did`, deriving and registering
+    /// its public half too. Used by the delegation FFI entry point, which
+    /// receives the delegator's own signing key rather than holding it
+    /// server-side.
+    pub fn load_signing_key(&mut self, did: String, secret_key_bytes: &[u8]) -> Result<(), CapabilityError> {
+        let secret = SecretKey::from_bytes(secret_key_bytes).map_err(|_| CapabilityError::MalformedKey)?;
+        let public = PublicKey::from(&secret);
+        self.verify_keys.insert(did.clone(), public);
+        self.signing_keys.insert(did, Keypair { secret, public });
+        Ok(())
+    }
+
+    /// Register a known public key for `did`, letting this signer verify
+    /// (but not produce) signatures from that DID.
+    pub fn trust_public_key(&mut self, did: String, public_key_bytes: &[u8]) -> Result<(), CapabilityError> {
+        let public = PublicKey::from_bytes(public_key_bytes).map_err(|_| CapabilityError::MalformedKey)?;
+        self.verify_keys.insert(did, public);
+        Ok(())
+    }
+}
+
+impl TokenSigner for Ed25519Signer {
+    fn sign(&self, issuer: &str, payload: &str) -> Result<String, CapabilityError> {
+        let keypair = self.signing_keys.get(issuer).ok_or(CapabilityError::SigningKeyUnavailable)?;
+        let signature = keypair.sign(payload.as_bytes());
+        Ok(base64::encode(&signature.to_bytes()[..]))
+    }
+
+    fn verify(&self, issuer: &str, payload: &str, signature: &str) -> bool {
+        let public_key = match self.verify_keys.get(issuer) {
+            Some(key) => key,
+            None => return false,
+        };
+        let signature_bytes = match base64::decode(signature) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let signature = match Signature::from_bytes(&signature_bytes) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        public_key.verify(payload.as_bytes(), &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer_with(dids: &[&str]) -> Ed25519Signer {
+        let mut signer = Ed25519Signer::new();
+        for did in dids {
+            signer.generate_signing_key(did.to_string());
+        }
+        signer
+    }
+
+    fn capability(resource: &str, ability: &str) -> Capability {
+        Capability { resource: resource.to_string(), ability: ability.to_string() }
+    }
+
+    /// Mint a root token with a fixed `jti` and no expiry, for tests that
+    /// don't care about either.
+    fn root_token<S: TokenSigner>(signer: &S, issuer: &str, audience: &str, capabilities: Vec<Capability>) -> CapabilityToken {
+        CapabilityToken::root(signer, issuer.to_string(), audience.to_string(), capabilities, "root-jti".to_string(), None).unwrap()
+    }
+
+    #[test]
+    fn root_token_verifies_against_its_issuer() {
+        let signer = signer_with(&["owner"]);
+        let root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        assert!(root.verify(&signer, "owner", &(), 0).is_ok());
+    }
+
+    #[test]
+    fn verify_rejects_wrong_root_issuer() {
+        let signer = signer_with(&["owner"]);
+        let root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        match root.verify(&signer, "someone-else", &(), 0) {
+            Err(CapabilityError::RootIssuerMismatch) => {}
+            other => panic!("expected RootIssuerMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_rejects_tampered_signature() {
+        let signer = signer_with(&["owner"]);
+        let mut root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        // Widen the granted ability after signing, without re-signing -
+        // the signature was only ever valid for the original payload.
+        root.capabilities[0].ability = "todo/delete".to_string();
+
+        match root.verify(&signer, "owner", &(), 0) {
+            Err(CapabilityError::InvalidSignature) => {}
+            other => panic!("expected InvalidSignature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn delegate_rejects_expanded_capabilities() {
+        let signer = signer_with(&["owner", "alice"]);
+        let root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        let result = root.delegate(
+            &signer,
+            "bob".to_string(),
+            vec![capability("toodle:1", "label/write")],
+            "delegated-jti".to_string(),
+            None,
+        );
+
+        match result {
+            Err(CapabilityError::CapabilitiesExpanded) => {}
+            other => panic!("expected CapabilitiesExpanded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn delegate_rejects_expiry_outliving_the_parent() {
+        let signer = signer_with(&["owner", "alice"]);
+        let root = CapabilityToken::root(
+            &signer,
+            "owner".to_string(),
+            "alice".to_string(),
+            vec![capability("toodle:1", "todo/write")],
+            "root-jti".to_string(),
+            Some(1_000),
+        ).unwrap();
+
+        let result = root.delegate(
+            &signer,
+            "bob".to_string(),
+            vec![capability("toodle:1", "todo/write")],
+            "delegated-jti".to_string(),
+            Some(2_000),
+        );
+
+        match result {
+            Err(CapabilityError::LifetimeExpanded) => {}
+            other => panic!("expected LifetimeExpanded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn delegated_chain_verifies_and_authorizes() {
+        let signer = signer_with(&["owner", "alice"]);
+        let root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        let delegated = root.delegate(
+            &signer,
+            "bob".to_string(),
+            vec![capability("toodle:1", "todo/write")],
+            "delegated-jti".to_string(),
+            None,
+        ).unwrap();
+
+        assert!(delegated.authorize(&signer, "owner", &(), 0, "toodle:1", "todo/write").is_ok());
+    }
+
+    #[test]
+    fn verify_rejects_an_expired_token() {
+        let signer = signer_with(&["owner"]);
+        let root = CapabilityToken::root(
+            &signer,
+            "owner".to_string(),
+            "alice".to_string(),
+            vec![capability("toodle:1", "todo/write")],
+            "root-jti".to_string(),
+            Some(1_000),
+        ).unwrap();
+
+        match root.verify(&signer, "owner", &(), 1_000) {
+            Err(CapabilityError::Expired) => {}
+            other => panic!("expected Expired, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_accepts_an_unexpired_token() {
+        let signer = signer_with(&["owner"]);
+        let root = CapabilityToken::root(
+            &signer,
+            "owner".to_string(),
+            "alice".to_string(),
+            vec![capability("toodle:1", "todo/write")],
+            "root-jti".to_string(),
+            Some(1_000),
+        ).unwrap();
+
+        assert!(root.verify(&signer, "owner", &(), 999).is_ok());
+    }
+
+    #[test]
+    fn verify_rejects_a_revoked_jti() {
+        struct Revoked;
+        impl RevocationList for Revoked {
+            fn is_revoked(&self, jti: &str) -> bool {
+                jti == "root-jti"
+            }
+        }
+
+        let signer = signer_with(&["owner"]);
+        let root = root_token(&signer, "owner", "alice", vec![capability("toodle:1", "todo/write")]);
+
+        match root.verify(&signer, "owner", &Revoked, 0) {
+            Err(CapabilityError::Revoked) => {}
+            other => panic!("expected Revoked, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn signing_payload_does_not_collide_across_field_boundaries() {
+        // Without length-prefixing, `issuer="owner", audience="alice|evil:admin",
+        // capabilities=[]` and `issuer="owner", audience="alice",
+        // capabilities=[{resource:"evil", ability:"admin"}]` both joined to
+        // the same bare-delimited string `"owner|alice|evil:admin"`.
+        let no_capabilities = signing_payload("owner", "alice|evil:admin", &[], "jti", None);
+        let with_capability = signing_payload("owner", "alice", &[capability("evil", "admin")], "jti", None);
+
+        assert_ne!(no_capabilities, with_capability);
+    }
+
+    #[test]
+    fn signing_payload_does_not_collide_across_the_expiry_field() {
+        // A token with jti="a1" and no expiry must sign differently from one
+        // with jti="a" and expires_at=Some(1), whose "1" would run on from
+        // the jti without a length prefix - exercise the same
+        // boundary-collision class of bug for the newly added fields.
+        let no_expiry = signing_payload("owner", "alice", &[], "a1", None);
+        let with_expiry = signing_payload("owner", "alice", &[], "a", Some(1));
+
+        assert_ne!(no_expiry, with_expiry);
+    }
+}
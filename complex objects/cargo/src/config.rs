@@ -0,0 +1,141 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! TOML-based `StoreConfig`, an alternative to the bare `uri: String` that
+//! `Toodle::new`/`new_toodle` take. A minimal `storage = { uri = "..." }`
+//! document is enough to construct a `Toodle`; `[sync]` and `[auth]` are
+//! optional and fall back to sane defaults.
+
+extern crate toml;
+
+use std::fmt;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default = "SyncConfig::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl SyncConfig {
+    fn default_interval_secs() -> u64 {
+        300
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> SyncConfig {
+        SyncConfig {
+            endpoint: None,
+            interval_secs: SyncConfig::default_interval_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub token_source: Option<String>,
+}
+
+/// A fully-typed store configuration, parsed from a TOML document.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreConfig {
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid store config: {}", self.0)
+    }
+}
+
+impl StoreConfig {
+    /// Parse `document` as TOML, rejecting unknown keys in any section
+    /// rather than silently ignoring them.
+    pub fn parse(document: &str) -> Result<StoreConfig, ConfigError> {
+        toml::from_str(document).map_err(|err| ConfigError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fills_in_sync_and_auth_defaults_when_absent() {
+        let config = StoreConfig::parse(r#"
+            [storage]
+            uri = "file:///tmp/store.db"
+        "#).unwrap();
+
+        assert_eq!(config.storage.uri, "file:///tmp/store.db");
+        assert_eq!(config.sync.endpoint, None);
+        assert_eq!(config.sync.interval_secs, SyncConfig::default_interval_secs());
+        assert_eq!(config.auth.token_source, None);
+    }
+
+    #[test]
+    fn parse_honors_explicit_sync_and_auth_sections() {
+        let config = StoreConfig::parse(r#"
+            [storage]
+            uri = "file:///tmp/store.db"
+
+            [sync]
+            endpoint = "https://sync.example.com"
+            interval_secs = 60
+
+            [auth]
+            token_source = "keychain"
+        "#).unwrap();
+
+        assert_eq!(config.sync.endpoint, Some("https://sync.example.com".to_string()));
+        assert_eq!(config.sync.interval_secs, 60);
+        assert_eq!(config.auth.token_source, Some("keychain".to_string()));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        let result = StoreConfig::parse(r#"
+            [storage]
+            uri = "file:///tmp/store.db"
+            bogus = "nope"
+        "#);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_rejects_missing_storage_section() {
+        let result = StoreConfig::parse(r#"
+            [sync]
+            endpoint = "https://sync.example.com"
+        "#);
+
+        assert!(result.is_err());
+    }
+}
@@ -12,7 +12,27 @@ extern crate ffi_utils;
 extern crate store;
 extern crate logins;
 extern crate list;
+extern crate serde;
+extern crate serde_json;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate reqwest;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate base64;
+#[macro_use]
+extern crate serde_derive;
 
+mod capability;
+mod config;
+// `oauth` pulls in `rand`/`sha2` for PKCE verifier/challenge generation and,
+// through `HttpTokenExchanger` below, the sync `reqwest` client - neither
+// targets wasm32. Browser/Electron callers go through the `wasm` module
+// instead, which doesn't expose login yet.
+#[cfg(not(target_arch = "wasm32"))]
+mod oauth;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+#[cfg(not(target_arch = "wasm32"))]
 use std::os::raw::{
     c_char
 };
@@ -20,11 +40,48 @@ use std::sync::{
     Arc,
 };
 
-use ffi_utils::strings::c_char_to_string;
+#[cfg(not(target_arch = "wasm32"))]
+use ffi_utils::strings::{c_char_to_string, string_to_c_char};
 use logins::LoginManager;
 use list::ListManager;
 use store::Store;
 
+pub use capability::{Capability, CapabilityError, CapabilityToken, Ed25519Signer, TokenSigner};
+pub use config::{ConfigError, StoreConfig};
+#[cfg(not(target_arch = "wasm32"))]
+pub use oauth::{PkceError, PkceSession, TokenExchanger, TokenResponse};
+
+/// Exchanges an authorization code for tokens over a real HTTP connection.
+#[cfg(not(target_arch = "wasm32"))]
+struct HttpTokenExchanger;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TokenExchanger for HttpTokenExchanger {
+    fn exchange(
+        &self,
+        token_endpoint: &str,
+        client_id: &str,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenResponse, String> {
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("code", code),
+            ("code_verifier", code_verifier),
+        ];
+        reqwest::Client::new()
+            .post(token_endpoint)
+            .form(&params)
+            .send()
+            .and_then(|mut response| response.json())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Clone)]
 pub struct Toodle {
     store: Arc<Store>,
     logins: Arc<LoginManager>,
@@ -40,32 +97,225 @@ impl Toodle {
             list: Arc::new(ListManager::new(store.clone()))
         }
     }
+
+    /// Construct a `Toodle` from a TOML `StoreConfig` document, alongside the
+    /// bare-`uri` constructor above. Sync endpoint/interval and auth token
+    /// source aren't wired into `Store` yet, but parsing and validating them
+    /// here means callers can start shipping config files today.
+    pub fn from_config_str(document: &str) -> Result<Toodle, ConfigError> {
+        let config = StoreConfig::parse(document)?;
+        Ok(Toodle::new(config.storage.uri))
+    }
+
+    /// The underlying `Store`, for callers outside this crate (e.g.
+    /// `webext-bridge`) that need to reach `Store` methods directly rather
+    /// than through the narrower `toodle_logins`/`toodle_list` handles.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Verify `token` against this store's owner DID and `logins`' trusted
+    /// key ring as of `now` (Unix seconds - the caller supplies it rather
+    /// than this reading a clock itself, since wasm32 callers have no OS
+    /// clock to read), then check it grants `ability` on `resource`. Every
+    /// `Store` operation that takes a capability token should go through
+    /// this before touching the store.
+    ///
+    /// Toodle-scoped resources are named `toodle:<owner DID>` everywhere a
+    /// caller builds one to pass in here - the Android FFI, the wasm
+    /// bindings, and `webext-bridge`'s HTTP/native-messaging routes all key
+    /// on the store's owner DID rather than any process-local registry id,
+    /// so a token delegated for use through one surface also authorizes the
+    /// equivalent action through the others.
+    ///
+    /// No surface plugs in a real `RevocationList` yet, so this only enforces
+    /// `token.expires_at`; a token can't be revoked mid-lifetime until one
+    /// of the FFI/HTTP surfaces is wired up with a revocation store to pass
+    /// through here instead of `()`.
+    pub fn authorize(&self, token: &CapabilityToken, resource: &str, ability: &str, now: i64) -> Result<(), CapabilityError> {
+        let mut signer = Ed25519Signer::new();
+        for (did, public_key) in self.logins.trusted_keys() {
+            signer.trust_public_key(did, &public_key)?;
+        }
+        token.authorize(&signer, &self.store.owner_did(), &(), now, resource, ability)
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 #[no_mangle]
 pub extern "C" fn new_toodle(uri: *const c_char) -> *mut Toodle {
     let uri = c_char_to_string(uri);
     Box::into_raw(Box::new(Toodle::new(uri)))
 }
 
+/// Construct a `Toodle` from a TOML `StoreConfig` document rather than a bare
+/// uri. Returns null if the document doesn't parse or contains unknown keys.
+#[cfg(not(target_arch = "wasm32"))]
+#[no_mangle]
+pub unsafe extern "C" fn new_toodle_from_config(config: *const c_char) -> *mut Toodle {
+    match Toodle::from_config_str(&c_char_to_string(config)) {
+        Ok(toodle) => Box::into_raw(Box::new(toodle)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 #[no_mangle]
 pub unsafe extern "C" fn toodle_destroy(toodle: *mut Toodle) {
     let _ = Box::from_raw(toodle);
 }
 
+/// Hands back a raw `LoginManager` handle with no capability check of its
+/// own - `LoginManager`/`Store` calls made directly through it bypass the
+/// `Toodle::authorize` gate entirely. Fine for the login flow itself, which
+/// predates capability tokens and has no `resource`/`ability` to check yet,
+/// but any future mutation added to this handle's surface should gate
+/// through `Toodle::authorize` the way `webext-bridge`/`wasm` do, not rely
+/// on this accessor staying unauthorized-by-default.
+#[cfg(not(target_arch = "wasm32"))]
 #[no_mangle]
 pub unsafe extern "C" fn toodle_logins(toodle: *mut Toodle) -> *mut Arc<LoginManager> {
     let toodle = &*toodle;
     Box::into_raw(Box::new(toodle.logins.clone()))
 }
 
+/// Hands back a raw `ListManager` handle with no capability check of its
+/// own, same caveat as `toodle_logins` above: it's on the caller not to use
+/// this to reach `ListManager` mutations without going through
+/// `Toodle::authorize` first.
+#[cfg(not(target_arch = "wasm32"))]
 #[no_mangle]
 pub unsafe extern "C" fn toodle_list(toodle: *mut Toodle) -> *mut Arc<ListManager> {
     let toodle = &*toodle;
     Box::into_raw(Box::new(toodle.list.clone()))
 }
 
- #[cfg(target_os="android")]
+/// Delegate a subset of `parent_token`'s capabilities to `audience`, signed
+/// with `delegator_signing_key` - the base64-encoded ed25519 secret key
+/// belonging to `parent_token`'s `audience` DID (the delegator, who becomes
+/// the new token's issuer). `capabilities` is a JSON array of
+/// `{ "resource": ..., "ability": ... }` objects and must already be
+/// satisfied by `parent_token`. `jti` identifies the new token for a
+/// caller-side revocation list; `expires_at` is a Unix timestamp (seconds)
+/// the new token stops validating at, or `0` for a token that never expires
+/// (and must not outlive `parent_token`'s own expiry, if it has one).
+/// Returns null on attenuation failure, malformed JSON, or a key that
+/// doesn't decode as an ed25519 secret key.
+#[cfg(not(target_arch = "wasm32"))]
+#[no_mangle]
+pub unsafe extern "C" fn toodle_delegate_capability(
+    parent_token: *const c_char,
+    audience: *const c_char,
+    capabilities: *const c_char,
+    delegator_signing_key: *const c_char,
+    jti: *const c_char,
+    expires_at: i64,
+) -> *mut c_char {
+    let parent: CapabilityToken = match serde_json::from_str(&c_char_to_string(parent_token)) {
+        Ok(token) => token,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let capabilities: Vec<Capability> = match serde_json::from_str(&c_char_to_string(capabilities)) {
+        Ok(capabilities) => capabilities,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let secret_key_bytes = match base64::decode(&c_char_to_string(delegator_signing_key)) {
+        Ok(bytes) => bytes,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let mut signer = Ed25519Signer::new();
+    if signer.load_signing_key(parent.audience.clone(), &secret_key_bytes).is_err() {
+        return std::ptr::null_mut();
+    }
+    let expires_at = if expires_at == 0 { None } else { Some(expires_at) };
+    match parent.delegate(&signer, c_char_to_string(audience), capabilities, c_char_to_string(jti), expires_at) {
+        Ok(token) => string_to_c_char(serde_json::to_string(&token).unwrap()),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Verify `token`'s proof chain against `store_owner` as of `now` (Unix
+/// seconds), returning `true` only if every signature validates, every
+/// delegation attenuates its parent, no hop has expired, and the chain's
+/// root was issued by the store owner. `key_ring` is a JSON object mapping
+/// each DID in the chain to its base64-encoded ed25519 *public* key, e.g.
+/// `{ "did:example:owner": "<base64>", ... }` - unlike a shared secret,
+/// handing out these public keys never lets a caller forge a signature for
+/// any of the listed DIDs. No revocation list is plumbed through this FFI
+/// yet, so a token can't be rejected before its `expires_at`.
+#[cfg(not(target_arch = "wasm32"))]
+#[no_mangle]
+pub unsafe extern "C" fn toodle_verify_token(
+    token: *const c_char,
+    store_owner: *const c_char,
+    key_ring: *const c_char,
+    now: i64,
+) -> bool {
+    let token: CapabilityToken = match serde_json::from_str(&c_char_to_string(token)) {
+        Ok(token) => token,
+        Err(_) => return false,
+    };
+    let key_ring: std::collections::HashMap<String, String> = match serde_json::from_str(&c_char_to_string(key_ring)) {
+        Ok(key_ring) => key_ring,
+        Err(_) => return false,
+    };
+    let mut signer = Ed25519Signer::new();
+    for (did, encoded_public_key) in key_ring {
+        let public_key_bytes = match base64::decode(&encoded_public_key) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        if signer.trust_public_key(did, &public_key_bytes).is_err() {
+            return false;
+        }
+    }
+    token.verify(&signer, &c_char_to_string(store_owner), &(), now).is_ok()
+}
+
+/// Begin an OAuth 2.0 + PKCE login. Returns the authorization URL for the
+/// caller to open; hang onto the returned `PkceSession` and pass it to
+/// `login_complete_pkce` once the redirect delivers a `code`.
+#[cfg(not(target_arch = "wasm32"))]
+#[no_mangle]
+pub unsafe extern "C" fn login_begin_pkce(
+    authorize_endpoint: *const c_char,
+    token_endpoint: *const c_char,
+    client_id: *const c_char,
+    redirect_uri: *const c_char,
+    session_out: *mut *mut PkceSession,
+) -> *mut c_char {
+    let (session, url) = PkceSession::begin(
+        &c_char_to_string(authorize_endpoint),
+        &c_char_to_string(token_endpoint),
+        &c_char_to_string(client_id),
+        &c_char_to_string(redirect_uri),
+    );
+    *session_out = Box::into_raw(Box::new(session));
+    string_to_c_char(url)
+}
+
+/// Complete a PKCE login: exchange `code` for tokens and persist them on
+/// `toodle`'s store. Consumes `session`.
+#[cfg(not(target_arch = "wasm32"))]
+#[no_mangle]
+pub unsafe extern "C" fn login_complete_pkce(
+    toodle: *mut Toodle,
+    session: *mut PkceSession,
+    code: *const c_char,
+    state: *const c_char,
+) -> bool {
+    let toodle = &*toodle;
+    let session = Box::from_raw(session);
+    match session.complete(&HttpTokenExchanger, &c_char_to_string(code), &c_char_to_string(state)) {
+        Ok(tokens) => {
+            toodle.store.store_auth_tokens(tokens);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+ #[cfg(all(target_os="android", not(target_arch = "wasm32")))]
  #[allow(non_snake_case)]
  pub mod android {
     extern crate jni;
@@ -93,10 +343,75 @@ pub unsafe extern "C" fn toodle_list(toodle: *mut Toodle) -> *mut Arc<ListManage
     }
 
     #[no_mangle]
-    pub unsafe extern fn Java_com_mozilla_toodle_RustToodle_newCategory(_: JNIEnv, _: JClass, toodle: *mut Toodle, _: JString) {
+    pub unsafe extern fn Java_com_mozilla_toodle_RustToodle_loginBeginPkce(
+        env: JNIEnv,
+        _: JClass,
+        authorize_endpoint: JString,
+        token_endpoint: JString,
+        client_id: JString,
+        redirect_uri: JString,
+    ) -> jlong {
+        let authorize_endpoint: String = env.get_string(authorize_endpoint).expect("Couldn't get authorize endpoint").into();
+        let token_endpoint: String = env.get_string(token_endpoint).expect("Couldn't get token endpoint").into();
+        let client_id: String = env.get_string(client_id).expect("Couldn't get client id").into();
+        let redirect_uri: String = env.get_string(redirect_uri).expect("Couldn't get redirect uri").into();
+        let (session, _url) = PkceSession::begin(&authorize_endpoint, &token_endpoint, &client_id, &redirect_uri);
+        Box::into_raw(Box::new(session)) as jlong
+    }
+
+    #[no_mangle]
+    pub unsafe extern fn Java_com_mozilla_toodle_RustToodle_loginCompletePkce(
+        env: JNIEnv,
+        _: JClass,
+        toodle: *mut Toodle,
+        session: *mut PkceSession,
+        code: JString,
+        state: JString,
+    ) -> bool {
+        let toodle = &*toodle;
+        let session = Box::from_raw(session);
+        let code: String = env.get_string(code).expect("Couldn't get code").into();
+        let state: String = env.get_string(state).expect("Couldn't get state").into();
+        match session.complete(&HttpTokenExchanger, &code, &state) {
+            Ok(tokens) => {
+                toodle.store.store_auth_tokens(tokens);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// The one concrete Android mutation path in this crate - gated the same
+    /// way `webext-bridge::handle_request`/`JsListManager::create_category`
+    /// gate theirs, against a `toodle:<owner DID>` resource (see the resource
+    /// naming note on `Toodle::authorize`). Returns `false` without touching
+    /// `ListManager` if `capability_token` doesn't parse or doesn't
+    /// authorize `list/write`.
+    #[no_mangle]
+    pub unsafe extern fn Java_com_mozilla_toodle_RustToodle_newCategory(
+        env: JNIEnv,
+        _: JClass,
+        toodle: *mut Toodle,
+        _: JString,
+        capability_token: JString,
+    ) -> bool {
         //let category_name: String = env.get_string(name).expect("Couldn't get category name").into();
         let toodle = &*toodle;
+        let capability_token: String = env.get_string(capability_token).expect("Couldn't get capability token").into();
+        let token: CapabilityToken = match serde_json::from_str(&capability_token) {
+            Ok(token) => token,
+            Err(_) => return false,
+        };
+        let resource = format!("toodle:{}", toodle.store.owner_did());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() as i64)
+            .unwrap_or(0);
+        if toodle.authorize(&token, &resource, "list/write", now).is_err() {
+            return false;
+        }
         let name: String = String::from("test");
         let _ = toodle.list.create_category(name);
+        true
     }
  }
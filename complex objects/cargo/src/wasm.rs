@@ -0,0 +1,118 @@
+// Copyright 2016 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! JS bindings for `wasm32-unknown-unknown`, mirroring the C/JNI FFI in the
+//! rest of this crate so the same sync-storage logic can run in a browser or
+//! Electron renderer and be driven from the existing native-messaging
+//! `Request`/`Response` schema.
+
+extern crate wasm_bindgen;
+extern crate serde_json;
+
+use std::sync::Arc;
+
+use self::wasm_bindgen::prelude::*;
+use self::wasm_bindgen::JsValue;
+
+use list::ListManager;
+use logins::LoginManager;
+
+use super::{CapabilityToken, Toodle};
+
+/// JS-visible handle wrapping a `Toodle`. `wasm-bindgen` hands this back to
+/// JS as an opaque object that round-trips through the exports below.
+#[wasm_bindgen]
+pub struct JsToodle {
+    inner: Toodle,
+}
+
+#[wasm_bindgen]
+impl JsToodle {
+    /// Equivalent of `new_toodle`/`Toodle::new` for JS callers.
+    #[wasm_bindgen(constructor)]
+    pub fn new(uri: String) -> JsToodle {
+        JsToodle { inner: Toodle::new(uri) }
+    }
+
+    /// Equivalent of `toodle_logins`: returns a handle JS can hold onto and
+    /// pass to whatever login bindings are exposed separately.
+    #[wasm_bindgen(js_name = logins)]
+    pub fn logins(&self) -> JsLoginManager {
+        JsLoginManager { inner: self.inner.logins.clone() }
+    }
+
+    /// Equivalent of `toodle_list`.
+    #[wasm_bindgen(js_name = list)]
+    pub fn list(&self) -> JsListManager {
+        JsListManager { inner: self.inner.list.clone(), toodle: self.inner.clone() }
+    }
+
+    /// Fetch an item by uuid and hand it back as a plain JS object, after
+    /// checking that `capability_token` (a JSON-encoded
+    /// `toodle::CapabilityToken`) authorizes `todo/read` on it, the same
+    /// check the FFI/native-messaging paths make via `Toodle::authorize`
+    /// before touching the store. `todo/read` is a distinct ability from
+    /// `todo/write` so a caller can be handed a read-only token for this
+    /// path without also being able to mutate the item. `now_unix_seconds` is
+    /// the caller's current time (e.g. JS `Date.now() / 1000`), since wasm32
+    /// has no OS clock of its own to check `capability_token`'s expiry
+    /// against. Returns `null` on a missing item or a token that doesn't
+    /// check out.
+    #[wasm_bindgen(js_name = fetchItem)]
+    pub fn fetch_item(&self, uuid: String, capability_token: String, now_unix_seconds: i64) -> JsValue {
+        let token: CapabilityToken = match serde_json::from_str(&capability_token) {
+            Ok(token) => token,
+            Err(_) => return JsValue::NULL,
+        };
+        let resource = format!("item:{}", uuid);
+        if self.inner.authorize(&token, &resource, "todo/read", now_unix_seconds).is_err() {
+            return JsValue::NULL;
+        }
+        match self.inner.store.fetch_item(uuid) {
+            Some(item) => JsValue::from_serde(&item).unwrap_or(JsValue::NULL),
+            None => JsValue::NULL,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct JsLoginManager {
+    inner: Arc<LoginManager>,
+}
+
+#[wasm_bindgen]
+pub struct JsListManager {
+    inner: Arc<ListManager>,
+    toodle: Toodle,
+}
+
+#[wasm_bindgen]
+impl JsListManager {
+    /// Equivalent of the JNI `newCategory` binding in the `android` module,
+    /// gated against a `toodle:<owner DID>` resource the same way (see the
+    /// resource naming note on `Toodle::authorize`). `now_unix_seconds` is
+    /// the caller's current time (e.g. JS `Date.now() / 1000`), used to
+    /// check `capability_token`'s expiry. Returns `false` without touching
+    /// `ListManager` if `capability_token` doesn't parse or doesn't
+    /// authorize `list/write`.
+    #[wasm_bindgen(js_name = createCategory)]
+    pub fn create_category(&self, name: String, capability_token: String, now_unix_seconds: i64) -> bool {
+        let token: CapabilityToken = match serde_json::from_str(&capability_token) {
+            Ok(token) => token,
+            Err(_) => return false,
+        };
+        let resource = format!("toodle:{}", self.toodle.store().owner_did());
+        if self.toodle.authorize(&token, &resource, "list/write", now_unix_seconds).is_err() {
+            return false;
+        }
+        let _ = self.inner.create_category(name);
+        true
+    }
+}
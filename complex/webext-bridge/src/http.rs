@@ -0,0 +1,254 @@
+//! Optional HTTP/REST front door onto the same `Registry` the native-messaging
+//! loop in `main` serves, gated behind the `http` feature so the plain
+//! stdin/stdout binary doesn't pay for a web server it didn't ask for.
+//!
+//! Routes are a thin mapping onto the existing `Request`/`Response` enums -
+//! `handle_request` does the actual work either way, so the two surfaces stay
+//! in sync by construction.
+
+extern crate actix_web;
+
+use std::sync::Arc;
+
+pub use self::actix_web::http::Method;
+use self::actix_web::http::header::AUTHORIZATION;
+use self::actix_web::http::StatusCode;
+use self::actix_web::middleware::cors::Cors;
+use self::actix_web::{server, App, HttpRequest, HttpResponse, Json, Path, State};
+
+use super::{handle_request, Error, Registry, Request};
+
+/// Which origins may talk to the store over HTTP, which methods those
+/// origins may use, and whether credentialed (cookie-bearing) requests are
+/// allowed.
+#[derive(Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allow_any_origin: bool,
+    pub allowed_methods: Vec<Method>,
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// `actix_web::middleware::cors::Cors::finish` panics if a wildcard
+    /// origin is combined with credentialed requests - that combination lets
+    /// any site read credentialed responses, which is exactly what CORS
+    /// exists to prevent. Reject it up front so a bad config fails loudly
+    /// instead of taking down the HTTP server thread.
+    fn validate(&self) -> Result<(), String> {
+        if self.allow_any_origin && self.allow_credentials {
+            Err("CorsConfig cannot combine allow_any_origin with allow_credentials".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewToodleBody {
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct CreateLabelBody {
+    name: String,
+    color: String,
+    capability_token: String,
+}
+
+#[derive(Deserialize)]
+struct CreateTodoBody {
+    name: String,
+    due: i64,
+    labels: Vec<String>,
+    capability_token: String,
+}
+
+#[derive(Deserialize)]
+struct SetDueBody {
+    // The route path is just `/todos/{id}` with no toodle segment, so the
+    // toodle it belongs to has to travel in the body instead.
+    toodle_id: i64,
+    // The item's uuid, used to authorize `todo/write` on `item:<uuid>` - the
+    // same resource identifier `AddLabel` and `JsToodle::fetch_item` use -
+    // rather than the numeric `{id}` in the path.
+    item_uuid: String,
+    due: i64,
+    capability_token: String,
+}
+
+/// Pull `capability_token` out of an `Authorization: Bearer <token>` header
+/// rather than the query string, for routes with no JSON body to carry it
+/// in. A query parameter travels in plain sight in server/proxy access logs
+/// and in any cross-origin `Referer` header for the lifetime of the
+/// request, letting anyone with log access replay it until its
+/// `expires_at` - a header isn't logged or forwarded that way by default.
+fn bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+}
+
+fn respond(response: super::Response) -> HttpResponse {
+    let status = match response {
+        super::Response::Err(ref err) => status_for(err),
+        _ => StatusCode::OK,
+    };
+    HttpResponse::build(status).json(response)
+}
+
+fn status_for(err: &Error) -> StatusCode {
+    match err.class() {
+        "NotFound" => StatusCode::NOT_FOUND,
+        "Unauthorized" => StatusCode::UNAUTHORIZED,
+        "BadRequest" => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn create_toodle(body: Json<NewToodleBody>, registry: State<Arc<Registry>>) -> HttpResponse {
+    respond(handle_request(&registry, Request::NewToodle { uri: body.uri.clone() }))
+}
+
+fn destroy_toodle(toodle_id: Path<i64>, req: HttpRequest<Arc<Registry>>, registry: State<Arc<Registry>>) -> HttpResponse {
+    let capability_token = match bearer_token(&req) {
+        Some(token) => token,
+        None => return respond(super::Response::Err(Error::BadRequest)),
+    };
+    respond(handle_request(
+        &registry,
+        Request::DestroyToodle {
+            toodle_id: *toodle_id,
+            capability_token,
+        },
+    ))
+}
+
+fn get_labels(toodle_id: Path<i64>, req: HttpRequest<Arc<Registry>>, registry: State<Arc<Registry>>) -> HttpResponse {
+    let capability_token = match bearer_token(&req) {
+        Some(token) => token,
+        None => return respond(super::Response::Err(Error::BadRequest)),
+    };
+    respond(handle_request(
+        &registry,
+        Request::GetLabels {
+            toodle_id: *toodle_id,
+            capability_token,
+        },
+    ))
+}
+
+fn create_label(toodle_id: Path<i64>, body: Json<CreateLabelBody>, registry: State<Arc<Registry>>) -> HttpResponse {
+    respond(handle_request(
+        &registry,
+        Request::CreateLabel {
+            toodle_id: *toodle_id,
+            name: body.name.clone(),
+            color: body.color.clone(),
+            capability_token: body.capability_token.clone(),
+        },
+    ))
+}
+
+fn create_todo(toodle_id: Path<i64>, body: Json<CreateTodoBody>, registry: State<Arc<Registry>>) -> HttpResponse {
+    respond(handle_request(
+        &registry,
+        Request::CreateTodo {
+            toodle_id: *toodle_id,
+            name: body.name.clone(),
+            due: body.due,
+            labels: body.labels.clone(),
+            capability_token: body.capability_token.clone(),
+        },
+    ))
+}
+
+fn set_due(id: Path<i64>, body: Json<SetDueBody>, registry: State<Arc<Registry>>) -> HttpResponse {
+    respond(handle_request(
+        &registry,
+        Request::SetDue {
+            toodle_id: body.toodle_id,
+            id: *id,
+            item_uuid: body.item_uuid.clone(),
+            due: body.due,
+            capability_token: body.capability_token.clone(),
+        },
+    ))
+}
+
+/// Start the REST API on `bind_addr`, serving `registry` alongside whatever
+/// else (e.g. the native-messaging loop) is already sharing it.
+///
+/// Returns an error instead of panicking if `cors` is misconfigured or the
+/// bind fails, so a caller running this on a background thread can log and
+/// move on rather than silently killing the HTTP surface.
+pub fn run(registry: Arc<Registry>, bind_addr: &str, cors: CorsConfig) -> Result<(), String> {
+    cors.validate()?;
+
+    server::new(move || {
+        let mut cors_middleware = Cors::build();
+        cors_middleware.allowed_methods(cors.allowed_methods.clone());
+        if cors.allow_credentials {
+            cors_middleware.supports_credentials();
+        }
+        if cors.allow_any_origin {
+            cors_middleware.send_wildcard();
+        } else {
+            for origin in &cors.allowed_origins {
+                cors_middleware.allowed_origin(origin);
+            }
+        }
+
+        App::with_state(registry.clone())
+            .middleware(cors_middleware.finish())
+            .resource("/toodles", |r| r.method(Method::POST).with(create_toodle))
+            .resource("/toodles/{toodle_id}", |r| r.method(Method::DELETE).with(destroy_toodle))
+            .resource("/toodles/{toodle_id}/labels", |r| {
+                r.method(Method::GET).with(get_labels);
+                r.method(Method::POST).with(create_label);
+            })
+            .resource("/toodles/{toodle_id}/todos", |r| r.method(Method::POST).with(create_todo))
+            .resource("/todos/{id}", |r| r.method(Method::PATCH).with(set_due))
+    })
+    .bind(bind_addr)
+    .map_err(|err| format!("couldn't bind HTTP server to {}: {}", bind_addr, err))?
+    .run();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(allow_any_origin: bool, allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allow_any_origin,
+            allowed_methods: vec![Method::GET],
+            allow_credentials,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_wildcard_origin_with_credentials() {
+        let result = cors(true, true).validate();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn validate_accepts_wildcard_origin_without_credentials() {
+        let result = cors(true, false).validate();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn validate_accepts_credentials_with_an_explicit_allow_list() {
+        let result = cors(false, true).validate();
+
+        assert!(result.is_ok());
+    }
+}
@@ -1,34 +1,126 @@
+extern crate byteorder;
 #[macro_use]
 extern crate serde_derive;
 
 extern crate serde;
 extern crate serde_json;
 extern crate toodle;
+extern crate list;
 
-use std::io::{self, Read};
+#[cfg(feature = "http")]
+mod http;
+
+use std::io::{self, Read, Write};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use serde::ser::{Serialize, Serializer, SerializeStruct};
+
+use toodle::{CapabilityToken, Toodle};
+use list::{Color, Item, Label};
 
-use toodle::Toodle;
+/// The live set of `Toodle`s, shared between the stdin/stdout
+/// native-messaging loop and the optional HTTP server so both surfaces serve
+/// the same store concurrently.
+pub struct Registry {
+    toodles: Mutex<HashMap<i64, Toodle>>,
+    next_toodle_id: AtomicI64,
+}
+
+impl Registry {
+    fn new() -> Registry {
+        Registry {
+            toodles: Mutex::new(HashMap::new()),
+            next_toodle_id: AtomicI64::new(1),
+        }
+    }
+}
 
-enum Error {
+/// A single, machine-readable error shape shared by every `Store`,
+/// `LoginManager`, and `ListManager` failure that can surface over the
+/// native-messaging protocol.
+///
+/// `class` is what callers should branch on; `message` is for logs/humans;
+/// `entity` names the id that was missing or invalid, when there is one.
+#[derive(Debug)]
+pub enum Error {
+    NotFound { entity: Option<String> },
+    Unauthorized,
     BadRequest,
-    BadToodle,
-    BadLabel,
+    StorageError(String),
+}
+
+impl Error {
+    pub(crate) fn class(&self) -> &'static str {
+        match *self {
+            Error::NotFound { .. } => "NotFound",
+            Error::Unauthorized => "Unauthorized",
+            Error::BadRequest => "BadRequest",
+            Error::StorageError(_) => "StorageError",
+        }
+    }
+
+    fn message(&self) -> String {
+        match *self {
+            Error::NotFound { ref entity } => match *entity {
+                Some(ref entity) => format!("{} not found", entity),
+                None => "not found".to_string(),
+            },
+            Error::Unauthorized => "not authorized for this operation".to_string(),
+            Error::BadRequest => "malformed or truncated request".to_string(),
+            Error::StorageError(ref message) => message.clone(),
+        }
+    }
+
+    fn entity(&self) -> Option<String> {
+        match *self {
+            Error::NotFound { ref entity } => entity.clone(),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("class", self.class())?;
+        state.serialize_field("message", &self.message())?;
+        state.serialize_field("entity", &self.entity())?;
+        state.end()
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
-enum Request {
+pub(crate) enum Request {
     NewToodle { uri: String },
-    DestroyToodle { toodle_id: i64 },
+    DestroyToodle {
+        toodle_id: i64,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing
+        /// `toodle/destroy` on this toodle.
+        capability_token: String,
+    },
 
-    GetLabels,
+    GetLabels {
+        toodle_id: i64,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing `label/read`
+        /// on this toodle.
+        capability_token: String,
+    },
     GetTodos { labels: Option<Vec<String>> },
 
     CreateTodo {
+        toodle_id: i64,
         name: String,
         due: i64,
         labels: Vec<String>,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing `todo/write`
+        /// on this toodle.
+        capability_token: String,
     },
     DeleteTodo { id: i64 },
     MarkCompleted { id: i64 },
@@ -37,110 +129,280 @@ enum Request {
         toodle_id: i64,
         name: String,
         color: String,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing `label/write`
+        /// on this toodle.
+        capability_token: String,
     },
     AddLabel {
         toodle_id: i64,
         item_uuid: String,
         label: Label,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing `todo/write`
+        /// on `item_uuid`.
+        capability_token: String,
+    },
+    SetDue {
+        toodle_id: i64,
+        /// Untrusted - kept only because the HTTP route's path carries it.
+        /// The mutation is keyed by `item_uuid` (re-fetched server-side), not
+        /// this field, so an `item_uuid` that doesn't match it can't be used
+        /// to retarget the update onto a different item.
+        id: i64,
+        item_uuid: String,
+        due: i64,
+        /// JSON-encoded `toodle::CapabilityToken` authorizing `todo/write`
+        /// on `item_uuid`.
+        capability_token: String,
     },
-    SetDue { id: i64, due: i64 },
 }
 
 impl Request {
-    fn read_from(&mut input: BufRead) -> Result<Request, Error> {
-        let length = input.read_u32::<NativeEndian>();
-        let mut message = input.take(length);
-        serde_json::from_reader(message).map_err(|_| Error::BadRequest)
+    fn read_from<R: Read>(input: &mut R) -> Result<Request, Error> {
+        let length = input.read_u32::<NativeEndian>().map_err(|_| Error::BadRequest)?;
+        let mut message = vec![0u8; length as usize];
+        input.read_exact(&mut message).map_err(|_| Error::BadRequest)?;
+        serde_json::from_slice(&message).map_err(|_| Error::BadRequest)
     }
 }
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
-enum Response {
+pub(crate) enum Response {
     NewToodle { toodle_id: i64 },
     DestroyToodle { destroyed: bool },
     CreateLabel { label: Label },
+    Labels { labels: Vec<Label> },
     CreateItem { item: Item },
     AddLabel,
+    Updated { updated: bool },
 
     Err(Error),
 }
 
 impl Response {
-    fn write_to(&self, output: Write) -> Result<()> {
-        let message = serde_json::to_vec(self)?;
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        // A response must always reach the client as a well-formed frame,
+        // even if `self` somehow fails to serialize - fall back to an error
+        // frame describing that failure rather than propagating a write with
+        // no frame at all.
+        let message = serde_json::to_vec(self).unwrap_or_else(|err| {
+            serde_json::to_vec(&Response::Err(Error::StorageError(err.to_string())))
+                .expect("a plain Error must always serialize")
+        });
         output.write_u32::<NativeEndian>(message.len() as u32)?;
-        output.write_all(message)
+        output.write_all(&message)
     }
 }
 
-fn main() {
-    let mut toodles = HashMap::new::<i64, Toodle>();
-    let mut next_toodle_id = 1i64;
-
-    let stdin = io::stdin();
-    let stdout = io::stdout();
+/// The `toodle:<owner_did>` resource `authorize` checks a toodle-scoped
+/// capability against (see the resource naming note on
+/// `toodle::Toodle::authorize`) - this registry's own `toodle_id` is only a
+/// process-local handle reused across restarts, so it can't be the resource
+/// a token is minted for.
+fn toodle_resource(toodle: &Toodle) -> String {
+    format!("toodle:{}", toodle.store().owner_did())
+}
 
-    let mut input = stdin.lock();
-    let mut output = stdout.lock();
+/// Parse `capability_token` and check it authorizes `ability` on `resource`
+/// against `toodle`'s store owner and trusted key ring, via
+/// `toodle::Toodle::authorize`. This host runs natively (never as wasm32),
+/// so unlike the wasm bindings it can read the system clock itself to check
+/// `capability_token`'s expiry rather than taking `now` from the caller.
+fn authorize(toodle: &Toodle, capability_token: &str, resource: &str, ability: &str) -> Result<(), Error> {
+    let token: CapabilityToken = serde_json::from_str(capability_token).map_err(|_| Error::BadRequest)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or(0);
+    toodle.authorize(&token, resource, ability, now).map_err(|_| Error::Unauthorized)
+}
 
-    loop {
-        let response = match Request::read_from(input) {
-            Ok(request) => {
-                match request {
-                    Request::NewToodle { uri } => {
-                        let toodle = Toodle::new(uri);
-                        toodles.insert(next_toodle_id, toodle);
-                        let response = Response::NewToodle { toodle_id: next_toodle_id };
-                        next_toodle_id += 1;
-                        response
+/// Dispatch a single `Request` against `registry`, shared by the
+/// native-messaging loop in `main` and the optional HTTP server in `http`.
+pub(crate) fn handle_request(registry: &Registry, request: Request) -> Response {
+    match request {
+        Request::NewToodle { uri } => {
+            let toodle = Toodle::new(uri);
+            let toodle_id = registry.next_toodle_id.fetch_add(1, Ordering::SeqCst);
+            registry.toodles.lock().unwrap().insert(toodle_id, toodle);
+            Response::NewToodle { toodle_id }
+        }
+        Request::DestroyToodle { toodle_id, capability_token } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    let resource = toodle_resource(toodle);
+                    match authorize(toodle, &capability_token, &resource, "toodle/destroy") {
+                        Ok(()) => {}
+                        Err(err) => return Response::Err(err),
                     }
-                    Request::DestroyToodle { toodle_id } => {
-                        let maybe_toodle = toodles.remove(toodle_id);
-                        Response::DestroyToodle { destroyed: maybe_toodle.is_some() }
+                }
+                None => return Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+            let maybe_toodle = registry.toodles.lock().unwrap().remove(&toodle_id);
+            Response::DestroyToodle { destroyed: maybe_toodle.is_some() }
+        }
+        Request::CreateLabel {
+            toodle_id,
+            name,
+            color,
+            capability_token,
+        } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    let resource = toodle_resource(toodle);
+                    match authorize(toodle, &capability_token, &resource, "label/write") {
+                        Ok(()) => match toodle.store().create_label(name, color) {
+                            Some(label) => Response::CreateLabel { label },
+                            None => Response::Err(Error::StorageError("couldn't create label".to_string())),
+                        },
+                        Err(err) => Response::Err(err),
                     }
-                    Request::CreateLabel {
-                        toodle_id,
-                        name,
-                        color,
-                    } => {
-                        match toodles.get(toodle_id) {
-                            Some(toodle) => {
-                                match toodle.store.create_label(name, color) {
-                                    Some(label) => Response::CreateLabel { label },
-                                    None => Response::Err(BadLabel),
+                }
+                None => Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+        }
+        Request::AddLabel {
+            toodle_id,
+            item_uuid,
+            label,
+            capability_token,
+        } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    let resource = format!("item:{}", item_uuid);
+                    match authorize(toodle, &capability_token, &resource, "todo/write") {
+                        Ok(()) => match toodle.store().fetch_item(item_uuid.clone()) {
+                            Some(mut item) => {
+                                if !item.labels.contains(&label) {
+                                    item.labels.push(label);
+                                    toodle.store().update_item(&item);
                                 }
+                                Response::AddLabel
                             }
-                            None => Response::Err(BadToodle),
-                        }
+                            None => Response::Err(Error::NotFound { entity: Some(item_uuid) }),
+                        },
+                        Err(err) => Response::Err(err),
                     }
-                    Request::AddLabel {
-                        toodle_id,
-                        item_uuid,
-                        label,
-                    } => {
-                        match toodles.get(toodle_id) {
-                            Some(toodle) => {
-                                match toodle.store.fetch_item(item_uuid) {
-                                    Some(mut item) => {
-                                        if !item.labels.contains(label) {
-                                            item.labels.push(label);
-                                            toodle.store.update_item(&item);
-                                        }
-                                        Response::AddLabel
-                                    }
-                                    None => Response::Err(BadItem),
-                                }
-                            }
-                            None => Response::Err(BadToodle),
-                        }
+                }
+                None => Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+        }
+        Request::GetLabels { toodle_id, capability_token } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    let resource = toodle_resource(toodle);
+                    match authorize(toodle, &capability_token, &resource, "label/read") {
+                        Ok(()) => Response::Labels { labels: toodle.store().labels() },
+                        Err(err) => Response::Err(err),
+                    }
+                }
+                None => Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+        }
+        Request::CreateTodo {
+            toodle_id,
+            name,
+            due,
+            labels,
+            capability_token,
+        } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    let resource = toodle_resource(toodle);
+                    match authorize(toodle, &capability_token, &resource, "todo/write") {
+                        Ok(()) => match toodle.store().create_item(name, due, labels) {
+                            Some(item) => Response::CreateItem { item },
+                            None => Response::Err(Error::StorageError("couldn't create item".to_string())),
+                        },
+                        Err(err) => Response::Err(err),
                     }
                 }
+                None => Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+        }
+        Request::SetDue {
+            toodle_id,
+            id: _,
+            item_uuid,
+            due,
+            capability_token,
+        } => {
+            match registry.toodles.lock().unwrap().get(&toodle_id) {
+                Some(toodle) => {
+                    // Keyed by `item_uuid`, the same identifier `AddLabel`
+                    // and `JsToodle::fetch_item` authorize against, so one
+                    // `item:<uuid>` resource name covers reads (`todo/read`)
+                    // and writes (`todo/write`) across all of them.
+                    let resource = format!("item:{}", item_uuid);
+                    match authorize(toodle, &capability_token, &resource, "todo/write") {
+                        // The caller's numeric `id` is untrusted - it's never
+                        // checked against `item_uuid`, so a token scoped to
+                        // one item could otherwise be paired with any other
+                        // item's id to retarget the mutation. Look the item
+                        // up by the uuid we just authorized and mutate that
+                        // row's own id instead, the same way `AddLabel` fetches
+                        // by `item_uuid` rather than trusting a separate id.
+                        Ok(()) => match toodle.store().fetch_item(item_uuid.clone()) {
+                            Some(item) => Response::Updated { updated: toodle.store().set_due(item.id, due) },
+                            None => Response::Err(Error::NotFound { entity: Some(item_uuid) }),
+                        },
+                        Err(err) => Response::Err(err),
+                    }
+                }
+                None => Response::Err(Error::NotFound { entity: Some(format!("toodle {}", toodle_id)) }),
+            }
+        }
+        // GetTodos, DeleteTodo, and MarkCompleted aren't implemented yet.
+        _ => Response::Err(Error::BadRequest),
+    }
+}
+
+fn main() {
+    let registry = Arc::new(Registry::new());
+
+    #[cfg(feature = "http")]
+    {
+        let registry = registry.clone();
+        std::thread::spawn(move || {
+            // No allow-list is wired up to a config source yet, so the
+            // default has to be closed rather than wildcard: a wildcard
+            // origin paired with the mutating `NewToodle` route (which has
+            // no capability check of its own - there's no store owner yet
+            // to check against) would let any website the user has open
+            // drive-by POST to this local server. Operators who need
+            // cross-origin access must opt in explicitly via
+            // `allowed_origins`.
+            let cors = http::CorsConfig {
+                allowed_origins: Vec::new(),
+                allow_any_origin: false,
+                allowed_methods: vec![
+                    http::Method::GET,
+                    http::Method::POST,
+                    http::Method::PATCH,
+                    http::Method::DELETE,
+                    http::Method::OPTIONS,
+                ],
+                allow_credentials: false,
+            };
+            if let Err(err) = http::run(registry, "127.0.0.1:8008", cors) {
+                eprintln!("HTTP server did not start: {}", err);
             }
-            Err(err) => Response::Error(err),
+        });
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+
+    loop {
+        let response = match Request::read_from(&mut input) {
+            Ok(request) => handle_request(&registry, request),
+            Err(err) => Response::Err(err),
         };
         response
-            .write_to(output)
+            .write_to(&mut output)
             .unwrap_or_else(|err| {
                                 eprintln!("Error handling request: {:?}", err);
                             });
@@ -172,3 +434,58 @@ impl Serialize for Item {
         state.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.write_u32::<NativeEndian>(body.len() as u32).unwrap();
+        buffer.extend_from_slice(body);
+        buffer
+    }
+
+    #[test]
+    fn read_from_parses_a_well_formed_frame() {
+        let buffer = frame(br#"{"type":"GetTodos","labels":null}"#);
+
+        let request = Request::read_from(&mut &buffer[..]).unwrap();
+
+        match request {
+            Request::GetTodos { labels } => assert_eq!(labels, None),
+            other => panic!("expected GetTodos, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_from_rejects_a_length_prefix_with_no_body() {
+        let mut buffer = Vec::new();
+        buffer.write_u32::<NativeEndian>(4).unwrap();
+
+        let result = Request::read_from(&mut &buffer[..]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_from_rejects_a_body_shorter_than_its_length_prefix() {
+        let body = br#"{"type":"GetTodos","labels":null}"#;
+        let mut buffer = Vec::new();
+        buffer.write_u32::<NativeEndian>((body.len() + 10) as u32).unwrap();
+        buffer.extend_from_slice(body);
+
+        let result = Request::read_from(&mut &buffer[..]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_from_rejects_a_truncated_length_prefix() {
+        let buffer = vec![0u8, 1u8];
+
+        let result = Request::read_from(&mut &buffer[..]);
+
+        assert!(result.is_err());
+    }
+}